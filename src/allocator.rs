@@ -0,0 +1,286 @@
+//! A sub-allocator for `vk::DeviceMemory`, modeled on the Vulkan Memory Allocator approach.
+//!
+//! Allocating raw `vk::DeviceMemory` once per resource is slow and runs into
+//! `maxMemoryAllocationCount` long before a real scene's buffer/image count does. Instead, the
+//! allocator requests a small number of large blocks from the driver, kept per memory-type index
+//! (a new block is added only when the existing ones have no room), and sub-allocates out of
+//! each block's free-list. Every later stage that needs device memory
+//! (vertex buffers, textures, depth images, ...) goes through this instead of calling
+//! `vkAllocateMemory` directly.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+
+/// The size of each `vk::DeviceMemory` block requested from the driver. Allocations larger than
+/// this get their own oversized block.
+const BLOCK_SIZE: vk::DeviceSize = 256 * 1024 * 1024;
+
+/// A range of a block handed out by `Allocator::allocate`. Passed back to `Allocator::free` to
+/// return it to the block's free-list.
+#[derive(Clone, Copy, Debug)]
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    memory_type_index: u32,
+    block_index: usize,
+}
+
+/// A free range within a block's free-list.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FreeRange {
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+}
+
+/// One large `vk::DeviceMemory` allocation that `Allocation`s are carved out of.
+#[derive(Debug)]
+struct Block {
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    free_ranges: Vec<FreeRange>,
+}
+
+impl Block {
+    /// Carves a `size`-byte range aligned to `alignment` out of this block's free-list, or
+    /// returns `None` if no free range is large enough.
+    fn carve(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<FreeRange> {
+        let index = self.free_ranges.iter().position(|range| {
+            let aligned_offset = align_up(range.offset, alignment);
+            range.size >= (aligned_offset - range.offset) + size
+        })?;
+
+        let range = self.free_ranges.remove(index);
+        let aligned_offset = align_up(range.offset, alignment);
+        let padding = aligned_offset - range.offset;
+
+        if padding > 0 {
+            self.free_ranges.push(FreeRange {
+                offset: range.offset,
+                size: padding,
+            });
+        }
+
+        let remaining = range.size - padding - size;
+        if remaining > 0 {
+            self.free_ranges.push(FreeRange {
+                offset: aligned_offset + size,
+                size: remaining,
+            });
+        }
+
+        Some(FreeRange {
+            offset: aligned_offset,
+            size,
+        })
+    }
+
+    /// Returns a `(offset, size)` range to the free-list, coalescing it with any free ranges
+    /// it's directly adjacent to.
+    fn release(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        self.free_ranges.push(FreeRange { offset, size });
+        self.free_ranges.sort_by_key(|range| range.offset);
+
+        let mut coalesced = Vec::<FreeRange>::with_capacity(self.free_ranges.len());
+        for range in self.free_ranges.drain(..) {
+            match coalesced.last_mut() {
+                Some(last) if last.offset + last.size == range.offset => last.size += range.size,
+                _ => coalesced.push(range),
+            }
+        }
+        self.free_ranges = coalesced;
+    }
+}
+
+/// Sub-allocates device memory out of a small number of large blocks, kept per memory-type index,
+/// instead of issuing one `vkAllocateMemory` call per resource.
+#[derive(Debug, Default)]
+pub struct Allocator {
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    blocks: HashMap<u32, Vec<Block>>,
+}
+
+impl Allocator {
+    /// Creates an allocator that will sub-allocate against `memory_properties`, as queried from
+    /// the physical device via `get_physical_device_memory_properties`.
+    pub fn new(memory_properties: vk::PhysicalDeviceMemoryProperties) -> Self {
+        Self {
+            memory_properties,
+            blocks: HashMap::new(),
+        }
+    }
+
+    /// Finds the index of the first memory type satisfying both `requirements.memory_type_bits`
+    /// and `flags`.
+    fn find_memory_type_index(
+        &self,
+        requirements: vk::MemoryRequirements,
+        flags: vk::MemoryPropertyFlags,
+    ) -> Result<u32> {
+        (0..self.memory_properties.memory_type_count)
+            .find(|&i| {
+                let suitable = (requirements.memory_type_bits & (1 << i)) != 0;
+                let memory_type = self.memory_properties.memory_types[i as usize];
+                suitable && memory_type.property_flags.contains(flags)
+            })
+            .ok_or_else(|| anyhow!("Failed to find memory type for allocation."))
+    }
+
+    /// Sub-allocates a range of device memory satisfying `requirements` with the given property
+    /// `flags`, requesting a new block from the driver if none of the existing blocks for that
+    /// memory type have room.
+    pub unsafe fn allocate(
+        &mut self,
+        device: &Device,
+        requirements: vk::MemoryRequirements,
+        flags: vk::MemoryPropertyFlags,
+    ) -> Result<Allocation> {
+        let memory_type_index = self.find_memory_type_index(requirements, flags)?;
+        let blocks = self.blocks.entry(memory_type_index).or_default();
+
+        for (block_index, block) in blocks.iter_mut().enumerate() {
+            if let Some(range) = block.carve(requirements.size, requirements.alignment) {
+                return Ok(Allocation {
+                    memory: block.memory,
+                    offset: range.offset,
+                    size: range.size,
+                    memory_type_index,
+                    block_index,
+                });
+            }
+        }
+
+        let block_size = BLOCK_SIZE.max(requirements.size);
+        let info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(block_size)
+            .memory_type_index(memory_type_index);
+        let memory = device.allocate_memory(&info, None)?;
+
+        let mut block = Block {
+            memory,
+            size: block_size,
+            free_ranges: vec![FreeRange {
+                offset: 0,
+                size: block_size,
+            }],
+        };
+        let range = block
+            .carve(requirements.size, requirements.alignment)
+            .ok_or_else(|| anyhow!("New block was too small for the requested allocation."))?;
+
+        let block_index = blocks.len();
+        blocks.push(block);
+
+        Ok(Allocation {
+            memory,
+            offset: range.offset,
+            size: range.size,
+            memory_type_index,
+            block_index,
+        })
+    }
+
+    /// Returns a previously-allocated range to its block's free-list, coalescing it with
+    /// adjacent free ranges.
+    pub fn free(&mut self, allocation: Allocation) {
+        if let Some(block) = self
+            .blocks
+            .get_mut(&allocation.memory_type_index)
+            .and_then(|blocks| blocks.get_mut(allocation.block_index))
+        {
+            block.release(allocation.offset, allocation.size);
+        }
+    }
+
+    /// Destroys every block's underlying `vk::DeviceMemory`.
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        for blocks in self.blocks.values() {
+            for block in blocks {
+                device.free_memory(block.memory, None);
+            }
+        }
+        self.blocks.clear();
+    }
+}
+
+/// Rounds `offset` up to the next multiple of `alignment`.
+fn align_up(offset: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    (offset + alignment - 1) / alignment * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(size: vk::DeviceSize) -> Block {
+        Block {
+            memory: vk::DeviceMemory::default(),
+            size,
+            free_ranges: vec![FreeRange { offset: 0, size }],
+        }
+    }
+
+    #[test]
+    fn align_up_rounds_up_to_the_next_multiple() {
+        assert_eq!(align_up(0, 16), 0);
+        assert_eq!(align_up(1, 16), 16);
+        assert_eq!(align_up(16, 16), 16);
+        assert_eq!(align_up(17, 16), 32);
+    }
+
+    #[test]
+    fn carve_splits_off_a_range_from_the_front() {
+        let mut block = block(100);
+        let range = block.carve(10, 1).unwrap();
+        assert_eq!(range.offset, 0);
+        assert_eq!(range.size, 10);
+        assert_eq!(block.free_ranges, vec![FreeRange { offset: 10, size: 90 }]);
+    }
+
+    #[test]
+    fn carve_pads_for_alignment_and_keeps_the_padding_free() {
+        let mut block = Block {
+            memory: vk::DeviceMemory::default(),
+            size: 100,
+            free_ranges: vec![FreeRange { offset: 5, size: 95 }],
+        };
+        let range = block.carve(10, 16).unwrap();
+        assert_eq!(range.offset, 16);
+        assert_eq!(range.size, 10);
+        assert_eq!(
+            block.free_ranges,
+            vec![
+                FreeRange { offset: 5, size: 11 },
+                FreeRange { offset: 26, size: 74 },
+            ]
+        );
+    }
+
+    #[test]
+    fn carve_returns_none_when_nothing_fits() {
+        let mut block = block(8);
+        assert!(block.carve(16, 1).is_none());
+    }
+
+    #[test]
+    fn release_coalesces_adjacent_free_ranges() {
+        let mut block = block(30);
+        let a = block.carve(10, 1).unwrap();
+        let b = block.carve(10, 1).unwrap();
+        // Free list is now just the trailing [20, 30) range.
+        assert_eq!(block.free_ranges, vec![FreeRange { offset: 20, size: 10 }]);
+
+        block.release(a.offset, a.size);
+        // [0, 10) and [20, 30) do not touch, so they stay separate.
+        assert_eq!(
+            block.free_ranges,
+            vec![FreeRange { offset: 0, size: 10 }, FreeRange { offset: 20, size: 10 }]
+        );
+
+        block.release(b.offset, b.size);
+        // Releasing [10, 20) bridges the two neighbors into a single [0, 30) range.
+        assert_eq!(block.free_ranges, vec![FreeRange { offset: 0, size: 30 }]);
+    }
+}
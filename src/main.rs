@@ -21,9 +21,14 @@ use winit::window::{Window, WindowId};
 
 use vulkanalia::loader::{LibloadingLoader, LIBRARY};
 use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::ExtDebugUtilsExtension;
 use vulkanalia::window as vk_window;
 use vulkanalia::Version;
 
+mod allocator;
+
+use allocator::Allocator;
+
 /// The Vulkan SDK version that started requiring the portability subset extension for macOS.
 const PORTABILITY_MACOS_VERSION: Version = Version::new(1, 3, 216);
 
@@ -31,6 +36,15 @@ const PORTABILITY_MACOS_VERSION: Version = Version::new(1, 3, 216);
 const VALIDATION_LAYER: vk::ExtensionName =
     vk::ExtensionName::from_bytes(b"VK_LAYER_KHRONOS_validation");
 
+/// `message_id_number` of `VUID-vkCmdEndDebugUtilsLabelEXT-commandBuffer-01912`, a known false
+/// positive emitted by affected versions of the Khronos validation layer.
+const VUID_CMD_END_DEBUG_UTILS_LABEL_01912: i32 = 0x56146426u32 as i32;
+
+/// The range of Khronos validation layer `spec_version`s known to emit the
+/// `VUID-vkCmdEndDebugUtilsLabelEXT-commandBuffer-01912` false positive.
+const VUID_CMD_END_DEBUG_UTILS_LABEL_01912_RANGE: (Version, Version) =
+    (Version::new(1, 3, 240), Version::new(1, 3, 250));
+
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         self.window = Some(
@@ -106,6 +120,7 @@ impl App {
             &self.entry.as_ref().unwrap(),
             &mut data,
         )?);
+        self.data = data;
         Ok(())
     }
 
@@ -118,20 +133,103 @@ impl App {
     unsafe fn destroy(&mut self) {
         info!("Destroying app.");
 
+        // No logical device exists yet at this stage of the app, so there is nothing for the
+        // allocator to have sub-allocated from; once device creation lands, free its blocks here
+        // (via `Allocator::destroy`) before the device itself is destroyed.
+        self.data.allocator = None;
+
+        if self.data.validation {
+            self.instance
+                .as_ref()
+                .unwrap()
+                .destroy_debug_utils_messenger_ext(self.data.messenger, None);
+        }
+
         self.instance.as_ref().unwrap().destroy_instance(None);
     }
 }
 
 /// The Vulkan handles and associated properties used by our Vulkan app.
-#[derive(Clone, Debug, Default)]
+#[derive(Debug, Default)]
 struct AppData {
     pub validation: bool,
+    /// State shared with `debug_callback` via the messenger's `user_data` pointer. Boxed so its
+    /// address stays stable across moves of `AppData` and outlives the `Instance`.
+    pub debug_user_data: Option<Box<DebugUserData>>,
+    /// The debug messenger created alongside the instance, covering validation for the entire
+    /// runtime rather than just `create_instance`/`destroy_instance`.
+    pub messenger: vk::DebugUtilsMessengerEXT,
+    /// The message severities the debug messenger was configured to report, driven by
+    /// `VK_VALIDATION_SEVERITY`.
+    pub validation_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    /// The message types the debug messenger was configured to report, driven by
+    /// `VK_VALIDATION_TYPE`.
+    pub validation_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    /// Sub-allocates device memory for buffers and images. Deliberately always `None` for now:
+    /// this app has no logical device yet, so there is nothing to construct an `Allocator` with.
+    /// Populated once device creation lands, at which point `App::destroy` must also start
+    /// calling `Allocator::destroy` here before the device itself is destroyed.
+    pub allocator: Option<Allocator>,
+}
+
+/// State handed to `debug_callback` through the debug messenger's `user_data` pointer.
+#[derive(Debug, Default)]
+struct DebugUserData {
+    /// The Khronos validation layer's properties, if the layer is installed, used to gate
+    /// suppression of messages that are only false positives on affected layer versions.
+    validation_layer: Option<vk::LayerProperties>,
+    /// `message_id_number`s that should be dropped without logging.
+    suppressed_message_ids: HashSet<i32>,
 }
 
 //================================================
 // Instance
 //================================================
 
+/// Parses a comma-separated list of severity names (`error`, `warning`, `info`, `verbose`) from
+/// `VK_VALIDATION_SEVERITY` into the corresponding bitmask. Unknown entries are ignored.
+fn parse_severity_mask(value: &str) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+    value
+        .split(',')
+        .map(str::trim)
+        .fold(vk::DebugUtilsMessageSeverityFlagsEXT::empty(), |mask, token| {
+            mask | match token {
+                "error" => vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+                "warning" => vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+                "info" => vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+                "verbose" => vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+                _ => vk::DebugUtilsMessageSeverityFlagsEXT::empty(),
+            }
+        })
+}
+
+/// Parses a comma-separated list of type names (`general`, `validation`, `performance`) from
+/// `VK_VALIDATION_TYPE` into the corresponding bitmask. Unknown entries are ignored.
+fn parse_type_mask(value: &str) -> vk::DebugUtilsMessageTypeFlagsEXT {
+    value
+        .split(',')
+        .map(str::trim)
+        .fold(vk::DebugUtilsMessageTypeFlagsEXT::empty(), |mask, token| {
+            mask | match token {
+                "general" => vk::DebugUtilsMessageTypeFlagsEXT::GENERAL,
+                "validation" => vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+                "performance" => vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+                _ => vk::DebugUtilsMessageTypeFlagsEXT::empty(),
+            }
+        })
+}
+
+/// Convenience preset mirroring vulkano's `errors_and_warnings`: only the ERROR and WARNING
+/// severities, with every message type enabled. A quick way to cut log noise on verbose drivers.
+/// Selected by setting `VK_VALIDATION_SEVERITY=errors_and_warnings`.
+fn errors_and_warnings(
+) -> (vk::DebugUtilsMessageSeverityFlagsEXT, vk::DebugUtilsMessageTypeFlagsEXT) {
+    (
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+        vk::DebugUtilsMessageTypeFlagsEXT::all(),
+    )
+}
+
 unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) -> Result<Instance> {
     // Application Info
     let application_info = vk::ApplicationInfo::builder()
@@ -142,8 +240,8 @@ unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) ->
         .api_version(vk::make_version(1, 0, 0));
 
     // Layers
-    let available_layers = entry
-        .enumerate_instance_layer_properties()?
+    let layer_properties = entry.enumerate_instance_layer_properties()?;
+    let available_layers = layer_properties
         .iter()
         .map(|l| l.layer_name)
         .collect::<HashSet<_>>();
@@ -152,6 +250,11 @@ unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) ->
         return Err(anyhow!("Validation layers requested but not supported."));
     }
 
+    let validation_layer_properties = layer_properties
+        .iter()
+        .find(|l| l.layer_name == VALIDATION_LAYER)
+        .copied();
+
     let layers = if data.validation {
         vec![VALIDATION_LAYER.as_ptr()]
     } else {
@@ -188,14 +291,44 @@ unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) ->
         .enabled_layer_names(&layers)
         .enabled_extension_names(&extensions);
 
+    // Debug user data lives on `AppData` (boxed, so its address is stable) so it outlives this
+    // function and can keep being read by `debug_callback` for the remainder of the app's life.
+    data.debug_user_data = Some(Box::new(DebugUserData {
+        validation_layer: validation_layer_properties,
+        suppressed_message_ids: default_suppressed_message_ids(validation_layer_properties),
+    }));
+    let user_data = data.debug_user_data.as_mut().unwrap().as_mut() as *mut DebugUserData;
+
+    // Filter which severities/types the messenger reports. Defaults to everything, matching the
+    // previous hard-coded behavior, unless overridden via environment variables.
+    // `VK_VALIDATION_SEVERITY=errors_and_warnings` is a shorthand for the `errors_and_warnings`
+    // preset and also governs the message type mask, overriding `VK_VALIDATION_TYPE`.
+    let severity_env = env::var("VK_VALIDATION_SEVERITY").ok();
+    if severity_env.as_deref() == Some("errors_and_warnings") {
+        let (severity, type_) = errors_and_warnings();
+        data.validation_severity = severity;
+        data.validation_type = type_;
+    } else {
+        data.validation_severity = severity_env
+            .as_deref()
+            .map(parse_severity_mask)
+            .unwrap_or(vk::DebugUtilsMessageSeverityFlagsEXT::all());
+        data.validation_type = env::var("VK_VALIDATION_TYPE")
+            .ok()
+            .map(|v| parse_type_mask(&v))
+            .unwrap_or(vk::DebugUtilsMessageTypeFlagsEXT::all());
+    }
+
     // Needs to be defined outside of the conditional since it needs to live until we are done calling create_instance
     let mut debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
         // The message_severity field allows you to specify all the types of severities you would like your callback to be called for
-        .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::all())
+        .message_severity(data.validation_severity)
         // Similarly the message_type field lets you filter which types of messages your callback is notified about
-        .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
+        .message_type(data.validation_type)
         // The user_callback field specifies the callback function
-        .user_callback(Some(debug_callback));
+        .user_callback(Some(debug_callback))
+        // Passed through to every invocation of user_callback as its user_data parameter.
+        .user_data(user_data as *mut c_void);
 
     if data.validation {
         // push it onto info's pointer chain
@@ -203,7 +336,105 @@ unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) ->
     }
 
     // Create instance
-    Ok(entry.create_instance(&info, None)?)
+    let instance = entry.create_instance(&info, None)?;
+
+    // Create a persistent debug messenger so validation keeps reporting for the lifetime of the
+    // app (rendering, resource creation/destruction), not just instance creation/destruction.
+    if data.validation {
+        data.messenger = instance.create_debug_utils_messenger_ext(&debug_info, None)?;
+    }
+
+    Ok(instance)
+}
+
+/// The suppression set to install by default for a given (possibly absent) validation layer,
+/// gated on the layer's `spec_version` so that fixed drivers still surface real errors.
+fn default_suppressed_message_ids(validation_layer: Option<vk::LayerProperties>) -> HashSet<i32> {
+    let mut suppressed = HashSet::new();
+
+    let (low, high) = VUID_CMD_END_DEBUG_UTILS_LABEL_01912_RANGE;
+    let affected = validation_layer
+        .map(|l| {
+            let spec_version = Version::from(l.spec_version);
+            spec_version >= low && spec_version <= high
+        })
+        .unwrap_or(false);
+    if affected {
+        suppressed.insert(VUID_CMD_END_DEBUG_UTILS_LABEL_01912);
+    }
+
+    suppressed
+}
+
+/// A Vulkan object referenced by a debug message, as named by the application (if at all).
+#[derive(Debug)]
+struct MessageObject {
+    object_type: vk::ObjectType,
+    object_name: Option<String>,
+}
+
+/// A structured, owned view of a `vk::DebugUtilsMessengerCallbackDataEXT`, parsed out of its raw
+/// pointers/arrays so the rest of the app never has to touch the unsafe FFI shape directly.
+#[derive(Debug)]
+struct Message {
+    id_name: String,
+    id_number: i32,
+    text: String,
+    queue_labels: Vec<String>,
+    cmd_buf_labels: Vec<String>,
+    objects: Vec<MessageObject>,
+}
+
+impl Message {
+    /// Extracts a `Message` from the callback data handed to `debug_callback`.
+    ///
+    /// # Safety
+    ///
+    /// `data`'s array fields (`queue_labels`, `cmd_buf_labels`, `objects`) must be valid for
+    /// their respective `_count`, and its C string fields must be null or point to a
+    /// null-terminated string, as guaranteed by the `VK_EXT_debug_utils` spec for the duration
+    /// of the callback.
+    unsafe fn from_raw(data: &vk::DebugUtilsMessengerCallbackDataEXT) -> Self {
+        let queue_labels = (0..data.queue_label_count as usize)
+            .map(|i| *data.queue_labels.add(i))
+            .filter_map(|label| cstr_to_string(label.label_name))
+            .collect();
+
+        let cmd_buf_labels = (0..data.cmd_buf_label_count as usize)
+            .map(|i| *data.cmd_buf_labels.add(i))
+            .filter_map(|label| cstr_to_string(label.label_name))
+            .collect();
+
+        let objects = (0..data.object_count as usize)
+            .map(|i| *data.objects.add(i))
+            .map(|object| MessageObject {
+                object_type: object.object_type,
+                object_name: cstr_to_string(object.object_name),
+            })
+            .collect();
+
+        Self {
+            id_name: cstr_to_string(data.message_id_name).unwrap_or_default(),
+            id_number: data.message_id_number,
+            text: cstr_to_string(data.message).unwrap_or_default(),
+            queue_labels,
+            cmd_buf_labels,
+            objects,
+        }
+    }
+}
+
+/// Reads a nullable, null-terminated C string into an owned `String`.
+///
+/// # Safety
+///
+/// `ptr` must be null or point to a valid null-terminated string.
+unsafe fn cstr_to_string(ptr: *const std::os::raw::c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+    }
 }
 
 /// Logs debug messages.
@@ -211,27 +442,42 @@ extern "system" fn debug_callback(
     severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     type_: vk::DebugUtilsMessageTypeFlagsEXT,
     data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _: *mut c_void,
+    user_data: *mut c_void,
 ) -> vk::Bool32 {
     // The data parameter refers to a vk::DebugUtilsMessengerCallbackDataEXT struct containing the details of the message itself
     // message – The debug message as a null-terminated string (*const c_char)
     // objects – Array of Vulkan object handles related to the message
     // object_count – Number of objects in array
-    let data = unsafe { *data };
-    let message = unsafe { CStr::from_ptr(data.message) }.to_string_lossy();
+    let message = unsafe { Message::from_raw(&*data) };
+
+    if !user_data.is_null() {
+        let user_data = unsafe { &*(user_data as *const DebugUserData) };
+        if user_data
+            .suppressed_message_ids
+            .contains(&message.id_number)
+        {
+            return vk::FALSE;
+        }
+    }
 
     if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
         // Message about behavior that is invalid and may cause crashes
-        error!("({:?}) {}", type_, message);
+        error!(
+            "[{}] {} (objects: {:?}, queue labels: {:?}, cmd buf labels: {:?})",
+            message.id_name, message.text, message.objects, message.queue_labels, message.cmd_buf_labels
+        );
     } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
         // Message about behavior that is not necessarily an error, but very likely a bug in your application
-        warn!("({:?}) {}", type_, message);
+        warn!(
+            "[{}] {} (objects: {:?}, queue labels: {:?}, cmd buf labels: {:?})",
+            message.id_name, message.text, message.objects, message.queue_labels, message.cmd_buf_labels
+        );
     } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
         // Informational message like the creation of a resource
-        debug!("({:?}) {}", type_, message);
+        debug!("[{}] {}", message.id_name, message.text);
     } else {
         // Diagnostic message
-        trace!("({:?}) {}", type_, message);
+        trace!("[{}] {}", message.id_name, message.text);
     }
 
     // The callback returns a (Vulkan) boolean that indicates if the Vulkan call that triggered the validation layer message should be aborted.